@@ -1,191 +1,662 @@
-use std::cell::RefCell;
-use std::ops::Deref;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::process;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
+use rand::Rng;
+
+/// The kind of movement a transaction performs.
+///
+/// A `Transfer` withdraws from one account and deposits into another, while
+/// `Deposit` and `Withdrawal` touch only a single account.
+#[derive(Clone)]
+enum Mode {
+    Deposit,
+    Withdrawal,
+    Transfer,
+}
+
+#[derive(Clone)]
 struct Transaction {
     id: i32,
     amount: f64,
+    mode: Mode,
     withdraw_account: String,
     deposit_account: String,
 }
 
-struct Account {
-    name: String,
-    balance: RefCell<f64>, // This is needed because later on when we .find() the account we need to update, we can't
-                           // update the balance directly because it is protected by an immutable reference returned by .find().
-                           // We need to create a mutable reference to the balance to update it, and RefCell allows the
-                           // .borrow_mut() function to return a mutable reference to the balance.
+/// Everything that can go wrong while parsing a single ledger line.
+#[derive(Debug)]
+enum ParseError {
+    WrongFieldCount(usize),
+    InvalidId(String),
+    InvalidAmount(String),
+    InvalidMode(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::WrongFieldCount(count) => write!(
+                f,
+                "expected 5 fields (id from_id to_id amount mode), found {}",
+                count
+            ),
+            ParseError::InvalidId(value) => write!(f, "invalid transaction id '{}'", value),
+            ParseError::InvalidAmount(value) => write!(f, "invalid amount '{}'", value),
+            ParseError::InvalidMode(value) => write!(f, "invalid mode '{}'", value),
+        }
+    }
+}
+
+impl Transaction {
+    /// Parse a single ledger line of the form `id from_id to_id amount mode`.
+    ///
+    /// `from_id`/`to_id` may be given as `-` when the mode does not use them
+    /// (for example the source account of a `Deposit`). Based on the mode, the
+    /// relevant ids are mapped onto the withdraw/deposit account fields that
+    /// `execute_transaction` already understands.
+    fn from_line(line: &str) -> Result<Transaction, ParseError> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ParseError::WrongFieldCount(fields.len()));
+        }
+
+        let id = fields[0]
+            .parse::<i32>()
+            .map_err(|_| ParseError::InvalidId(fields[0].to_string()))?;
+        let from_id = fields[1];
+        let to_id = fields[2];
+        let amount = fields[3]
+            .parse::<f64>()
+            .map_err(|_| ParseError::InvalidAmount(fields[3].to_string()))?;
+
+        let mode = match fields[4].to_lowercase().as_str() {
+            "deposit" => Mode::Deposit,
+            "withdrawal" => Mode::Withdrawal,
+            "transfer" => Mode::Transfer,
+            other => return Err(ParseError::InvalidMode(other.to_string())),
+        };
+
+        // Map the ids onto the withdraw/deposit account fields depending on the
+        // mode. A placeholder id (such as `-`) on an unused side is ignored.
+        let (withdraw_account, deposit_account) = match mode {
+            Mode::Deposit => (String::new(), to_id.to_string()),
+            Mode::Withdrawal => (from_id.to_string(), String::new()),
+            Mode::Transfer => (from_id.to_string(), to_id.to_string()),
+        };
+
+        Ok(Transaction {
+            id,
+            amount,
+            mode,
+            withdraw_account,
+            deposit_account,
+        })
+    }
+}
+
+/// Yields transactions from a slice according to a supplied permutation of
+/// indices. When no order is given it falls back to natural (0, 1, 2, ...)
+/// order, so the same iterator drives both the normal and `--randomize` runs.
+struct OrderedIterator<'a> {
+    transactions: &'a [Transaction],
+    order: Vec<usize>,
+    position: usize,
+}
+
+impl<'a> OrderedIterator<'a> {
+    /// Build an iterator over `transactions`. `order` is an optional permutation
+    /// of `0..transactions.len()`; `None` means natural order.
+    fn new(transactions: &'a [Transaction], order: Option<Vec<usize>>) -> OrderedIterator<'a> {
+        let order = order.unwrap_or_else(|| (0..transactions.len()).collect());
+        OrderedIterator {
+            transactions,
+            order,
+            position: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for OrderedIterator<'a> {
+    type Item = &'a Transaction;
+
+    fn next(&mut self) -> Option<&'a Transaction> {
+        if self.position >= self.order.len() {
+            return None;
+        }
+
+        let index = self.order[self.position];
+        self.position += 1;
+        self.transactions.get(index)
+    }
+}
+
+/// Produce a random permutation of `0..len` using a Fisher-Yates shuffle over
+/// the supplied random number generator.
+fn randomize_order<R: Rng + ?Sized>(len: usize, rng: &mut R) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..len).collect();
+    for i in (1..len).rev() {
+        let j = rng.gen_range(0..=i);
+        order.swap(i, j);
+    }
+    order
+}
+
+/// The account store maps an account name to its balance. Each balance has its
+/// own Mutex so that independent transfers (A1->A2 and A3->A1) can proceed in
+/// parallel instead of serializing on a single global lock. A BTreeMap also
+/// gives us a stable, sorted iteration order when we print the final balances.
+type Accounts = BTreeMap<String, Mutex<f64>>;
+
+/// The outcome of attempting a single transaction. A transaction only counts as
+/// `Succeeded` once it has passed the balance check and mutated the accounts;
+/// anything that stops it short (missing account, insufficient funds) is a
+/// `Failed` carrying a human-readable reason.
+enum TransactionResult {
+    Succeeded,
+    Failed { reason: String },
 }
 
-/// This executes a transaction on the supplied accounts
-fn execute_transaction(transaction: &Transaction, accounts: &[Account; 3]) {
+/// Running totals shared across every worker thread, printed once the work is
+/// done. Guarded by a Mutex so the increments stay race-free.
+struct Stats {
+    num_transactions: u32,
+    num_succeeded: u32,
+    num_failed: u32,
+    failures: Vec<String>,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            num_transactions: 0,
+            num_succeeded: 0,
+            num_failed: 0,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Fold a single transaction result into the totals, keeping the reason of
+    /// each failure so the summary can explain what went wrong.
+    fn record(&mut self, result: &TransactionResult) {
+        self.num_transactions += 1;
+        match result {
+            TransactionResult::Succeeded => self.num_succeeded += 1,
+            TransactionResult::Failed { reason } => {
+                self.num_failed += 1;
+                self.failures.push(reason.clone());
+            }
+        }
+    }
+}
+
+/// A single balance change, broadcast to the observer threads so they can keep
+/// a running audit log without ever touching an account lock themselves.
+struct BalanceEvent {
+    account: String,
+    delta: f64,
+    new_balance: f64,
+    tx_id: i32,
+}
+
+/// Observe the balance-change stream until every sender has been dropped,
+/// keeping a running audit log. At shutdown the full transaction history is
+/// reconstructed from the events that were seen.
+fn observe_balances(events: mpsc::Receiver<BalanceEvent>) {
+    let mut history: Vec<BalanceEvent> = Vec::new();
+
+    // Iterating the receiver blocks for each event and ends once the channel is
+    // closed (all senders dropped). No account lock is held here.
+    for event in events {
+        println!(
+            ">> [observer] tx {} {} {:+} -> {}",
+            event.tx_id, event.account, event.delta, event.new_balance
+        );
+        history.push(event);
+    }
+
+    // Replay the history we collected from the live feed.
+    println!("\nTransaction History (observed):");
+    for event in &history {
+        println!(
+            "tx {}: {} {:+} (balance {})",
+            event.tx_id, event.account, event.delta, event.new_balance
+        );
+    }
+}
+
+/// This executes a transaction on the supplied accounts.
+///
+/// Only the per-account locks for the accounts this transaction touches are
+/// acquired. When a transaction touches two accounts (a transfer), the locks
+/// are taken in canonical order (sorted by account name) so that two threads
+/// grabbing the same pair in opposite directions can never deadlock.
+///
+/// A withdrawal or transfer is refused (and reported as `Failed`) when the
+/// source balance cannot cover the amount, so balances never go negative.
+///
+/// Every successful balance mutation emits a `BalanceEvent` on `events` so the
+/// observer threads get a live feed of state changes.
+fn execute_transaction(
+    transaction: &Transaction,
+    accounts: &Accounts,
+    events: &mpsc::Sender<BalanceEvent>,
+) -> TransactionResult {
     // Print the transaction id
     println!(">> Current Transaction: {}", transaction.id);
 
-    // If a withdraw_account is given, withdraw the amount from the account
-    if transaction.withdraw_account.len() > 0 {
-        // Find the account to withdraw from
-        let withdraw_account = accounts
-            .iter()
-            .find(|account| account.name == transaction.withdraw_account);
-
-        // If the account exists, withdraw the amount from it
-        match withdraw_account {
-            None => println!(">> Account {} not found", transaction.withdraw_account),
-            Some(account) => {
-                println!(
-                    ">> Withdrawing {} from {}",
-                    transaction.amount, account.name
-                );
-
-                // Update the balance
-                *account.balance.borrow_mut() -= transaction.amount;
+    let has_withdraw = !transaction.withdraw_account.is_empty();
+    let has_deposit = !transaction.deposit_account.is_empty();
+
+    // A transfer whose source and destination are the same account would try to
+    // lock one (non-reentrant) Mutex twice and deadlock the worker. Such a
+    // transfer is also a no-op on the balance, so refuse it up front.
+    if has_withdraw && has_deposit && transaction.withdraw_account == transaction.deposit_account {
+        return TransactionResult::Failed {
+            reason: format!(
+                "transfer from {} to itself",
+                transaction.withdraw_account
+            ),
+        };
+    }
+
+    // When both accounts are involved we must lock them in a canonical order to
+    // avoid deadlock. Collect the names we touch and sort them before locking.
+    let mut to_lock: Vec<&str> = Vec::new();
+    if has_withdraw {
+        to_lock.push(&transaction.withdraw_account);
+    }
+    if has_deposit {
+        to_lock.push(&transaction.deposit_account);
+    }
+    to_lock.sort();
+
+    // Acquire each per-account lock up front, in sorted order, and keep the
+    // guards alive for the duration of the mutation.
+    let mut guards = BTreeMap::new();
+    for name in &to_lock {
+        match accounts.get(*name) {
+            None => {
+                return TransactionResult::Failed {
+                    reason: format!("account {} not found", name),
+                };
             }
+            Some(balance) => {
+                guards.insert(*name, balance.lock().unwrap());
+            }
+        }
+    }
+
+    // Refuse a withdrawal or transfer that would overdraw the source account.
+    // The check happens while both locks are held so the balance cannot change
+    // between the guard and the mutation below.
+    let overdraftable = matches!(transaction.mode, Mode::Withdrawal | Mode::Transfer);
+    if overdraftable && has_withdraw {
+        let balance = guards.get(transaction.withdraw_account.as_str()).unwrap();
+        if **balance < transaction.amount {
+            println!(
+                ">> Refusing transaction {}: {} has {} but needs {}",
+                transaction.id, transaction.withdraw_account, **balance, transaction.amount
+            );
+            return TransactionResult::Failed {
+                reason: format!(
+                    "insufficient funds in {} ({} < {})",
+                    transaction.withdraw_account, **balance, transaction.amount
+                ),
+            };
         }
     }
 
+    // If a withdraw_account is given, withdraw the amount from the account
+    if let Some(balance) = guards.get_mut(transaction.withdraw_account.as_str()) {
+        println!(
+            ">> Withdrawing {} from {}",
+            transaction.amount, transaction.withdraw_account
+        );
+
+        // Update the balance
+        **balance -= transaction.amount;
+
+        // Broadcast the change to the observers.
+        events
+            .send(BalanceEvent {
+                account: transaction.withdraw_account.clone(),
+                delta: -transaction.amount,
+                new_balance: **balance,
+                tx_id: transaction.id,
+            })
+            .unwrap();
+    }
+
     // If a deposit_account is given, deposit the amount to the account
-    if transaction.deposit_account.len() > 0 {
-        // Find the account to deposit to
-        let deposit_account = accounts
-            .iter()
-            .find(|account| account.name == transaction.deposit_account);
+    if let Some(balance) = guards.get_mut(transaction.deposit_account.as_str()) {
+        println!(
+            ">> Depositing {} to {}",
+            transaction.amount, transaction.deposit_account
+        );
 
-        // If the account exists, deposit the amount to it
-        match deposit_account {
-            None => println!(">> Account {} not found", transaction.deposit_account),
-            Some(account) => {
-                println!(">> Depositing {} to {}", transaction.amount, account.name);
+        // Update the balance
+        **balance += transaction.amount;
 
-                // Update the balance
-                *account.balance.borrow_mut() += transaction.amount;
-            }
-        }
+        // Broadcast the change to the observers.
+        events
+            .send(BalanceEvent {
+                account: transaction.deposit_account.clone(),
+                delta: transaction.amount,
+                new_balance: **balance,
+                tx_id: transaction.id,
+            })
+            .unwrap();
     }
 
     // Sleep for 2 seconds
     //thread::sleep(std::time::Duration::from_secs(2));
 
     println!(">> Transaction {} Completed", transaction.id);
+
+    TransactionResult::Succeeded
+}
+
+/// Load the ledger file at `path` into a Vec of transactions, reporting the
+/// line number of the first line that fails to parse.
+fn load_ledger(path: &str) -> Result<Vec<Transaction>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+
+    let mut transactions = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        // Skip blank lines and `#` comments so ledgers can be documented.
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        match Transaction::from_line(trimmed) {
+            Ok(transaction) => transactions.push(transaction),
+            Err(err) => return Err(format!("{}:{}: {}", path, number + 1, err)),
+        }
+    }
+
+    Ok(transactions)
 }
 
 fn main() {
-    // Create transactions to execute
-    let transactions: [Transaction; 7] = [
-        Transaction {
-            id: 1,
-            amount: 5.0,
-            withdraw_account: String::from("A1"),
-            deposit_account: String::from("A2"),
-        },
-        Transaction {
-            id: 2,
-            amount: 7.0,
-            withdraw_account: String::from("A3"),
-            deposit_account: String::from(""),
-        },
-        Transaction {
-            id: 3,
-            amount: 10.0,
-            withdraw_account: String::from("A2"),
-            deposit_account: String::from("A1"),
-        },
-        Transaction {
-            id: 4,
-            amount: 15.0,
-            withdraw_account: String::from("A1"),
-            deposit_account: String::from("A3"),
-        },
-        Transaction {
-            id: 5,
-            amount: 8.0,
-            withdraw_account: String::from("A1"),
-            deposit_account: String::from("A3"),
-        },
-        Transaction {
-            id: 6,
-            amount: 3.0,
-            withdraw_account: String::from("A2"),
-            deposit_account: String::from("A1"),
-        },
-        Transaction {
-            id: 7,
-            amount: 6.0,
-            withdraw_account: String::from("A3"),
-            deposit_account: String::from("A1"),
-        },
-    ];
-    // Store the length (because of some memory moving shenanigans in the while loop)
-    let transactions_length = transactions.len();
-
-    // Create an array of 10 accounts, all starting at 0.0
-    let accounts: [Account; 3] = [
-        Account {
-            name: String::from("A1"),
-            balance: RefCell::new(0.0),
-        },
-        Account {
-            name: String::from("A2"),
-            balance: RefCell::new(0.0),
-        },
-        Account {
-            name: String::from("A3"),
-            balance: RefCell::new(0.0),
-        },
-    ];
-
-    // Create an atomic reference counter for the array of accounts with a Mutex lock
-    let accounts_reference = Arc::new(Mutex::new(accounts));
-    // Create an atomic reference counter for the array of transactions with a Mutex lock
-    let transactions_reference = Arc::new(Mutex::new(transactions));
+    // Parse the command line: `cargo run <num_threads> <ledger_path> [--randomize]`.
+    // Flags may appear anywhere; the remaining positional args are the thread
+    // count and the ledger path, in that order.
+    let args: Vec<String> = std::env::args().collect();
+    let mut randomize = false;
+    let mut positional: Vec<&str> = Vec::new();
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
+            "--randomize" => randomize = true,
+            other => positional.push(other),
+        }
+    }
+
+    if positional.len() != 2 {
+        eprintln!("Usage: {} <num_threads> <ledger_path> [--randomize]", args[0]);
+        process::exit(1);
+    }
+
+    let num_threads = match positional[0].parse::<usize>() {
+        Ok(value) if value > 0 => value,
+        _ => {
+            eprintln!("num_threads must be a positive integer, got '{}'", positional[0]);
+            process::exit(1);
+        }
+    };
+
+    // Build the transaction list from the ledger file instead of a literal
+    // array, so the same binary can run arbitrary workloads.
+    let transactions = match load_ledger(positional[1]) {
+        Ok(transactions) => transactions,
+        Err(err) => {
+            eprintln!("Failed to load ledger: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // Build the account store from every account the ledger references, each
+    // balance starting at 0.0 and guarded by its own Mutex.
+    let mut accounts: Accounts = BTreeMap::new();
+    for transaction in &transactions {
+        for name in [&transaction.withdraw_account, &transaction.deposit_account] {
+            if !name.is_empty() {
+                accounts.entry(name.clone()).or_insert_with(|| Mutex::new(0.0));
+            }
+        }
+    }
+
+    // Create an atomic reference counter for the account store. The store itself
+    // is immutable (we only ever lock the inner balances), so no outer Mutex is
+    // needed - that is exactly what lets the transfers run in parallel.
+    let accounts_reference = Arc::new(accounts);
+    // Shared, Mutex-guarded tally of how each transaction turned out.
+    let stats_reference = Arc::new(Mutex::new(Stats::new()));
+
+    // Feed the workers through a shared work queue rather than spawning one
+    // thread per transaction. The producer pushes every transaction onto an
+    // mpsc channel and then drops the sender; each worker pulls from the shared
+    // receiver until the channel drains, which bounds concurrency to the size
+    // of the pool regardless of how many transactions there are.
+    //
+    // When `--randomize` is passed the producer dispatches in a fresh random
+    // permutation of the transaction slice rather than sequential index order.
+    let order = if randomize {
+        let mut rng = rand::thread_rng();
+        Some(randomize_order(transactions.len(), &mut rng))
+    } else {
+        None
+    };
+
+    let (sender, receiver) = mpsc::channel::<Transaction>();
+    for transaction in OrderedIterator::new(&transactions, order) {
+        sender.send(transaction.clone()).unwrap();
+    }
+    // Dropping the sender lets `recv` return Err once the queue is empty, which
+    // is how the workers know to exit.
+    drop(sender);
+
+    // The single receiver is shared across the pool behind a Mutex so only one
+    // worker pops a given transaction.
+    let receiver_reference = Arc::new(Mutex::new(receiver));
+
+    // A second channel carries balance-change events out to an observer thread.
+    // The observer never touches an account lock; it just listens to the feed
+    // and reconstructs the transaction history at shutdown.
+    let (event_sender, event_receiver) = mpsc::channel::<BalanceEvent>();
+    let observer = thread::spawn(move || observe_balances(event_receiver));
 
     // Keep track of the threads created.
     let mut handles: Vec<JoinHandle<()>> = vec![];
 
-    // Execute each transaction in a separate thread, with a max of 2 threads.
-    let mut iterator = 0;
-    while iterator < transactions_length {
+    // Spawn a fixed pool of N worker threads.
+    for worker in 0..num_threads {
         // Create a pointer to a reference to accounts_reference in the heap.
         let accounts_reference = Arc::clone(&accounts_reference);
-        // Create a pointer to a reference to transactions_reference in the heap.
-        let transactions_reference = Arc::clone(&transactions_reference);
+        // Create a pointer to the shared stats tally in the heap.
+        let stats_reference = Arc::clone(&stats_reference);
+        // Create a pointer to the shared work queue in the heap.
+        let receiver_reference = Arc::clone(&receiver_reference);
+        // Each worker gets its own sending end of the event channel.
+        let event_sender = event_sender.clone();
 
-        // Spawn a new thread.
+        // Spawn a new worker thread.
         let handle = thread::spawn(move || {
-            println!("Spawning thread {}", iterator + 1);
+            println!("Spawning worker {}", worker + 1);
 
-            // Get a mutex lock on the accounts_pointer (released when out of scope)
-            let accounts = accounts_reference.lock().unwrap();
-            // Get a mutex lock on the transactions_pointer (released when out of scope)
-            let transactions = transactions_reference.lock().unwrap();
+            // Pull transactions off the shared queue until it drains.
+            loop {
+                // Only hold the queue lock long enough to pop one transaction,
+                // so the execute step runs without blocking the other workers.
+                let transaction = {
+                    let queue = receiver_reference.lock().unwrap();
+                    queue.recv()
+                };
 
-            println!("Executing Thread {}", iterator + 1);
+                let transaction = match transaction {
+                    Ok(transaction) => transaction,
+                    // The sender has been dropped and the queue is empty.
+                    Err(_) => break,
+                };
 
-            // Get the transaction
-            let transaction = &transactions[iterator];
+                // Execute the transaction (each worker only locks the
+                // per-account balances it actually touches).
+                let result = execute_transaction(&transaction, &accounts_reference, &event_sender);
 
-            // Execute the transaction
-            execute_transaction(transaction, accounts.deref());
+                // Fold the outcome into the shared tally.
+                stats_reference.lock().unwrap().record(&result);
+            }
 
-            println!("Thread {} Completed", iterator + 1);
+            println!("Worker {} Completed", worker + 1);
         });
 
-        // Increment loop
-        iterator += 1;
-
         // Add the handle to the list of handles.
         handles.push(handle);
     }
 
+    // Drop the main thread's sender so the observer's feed ends once every
+    // worker (and its cloned sender) has finished.
+    drop(event_sender);
+
     // Wait for threads to finish
     for handle in handles {
         handle.join().unwrap();
     }
 
+    // Now that the workers are done, wait for the observer to drain the feed.
+    observer.join().unwrap();
+
     // Print the final balances
     println!("\nFinal Balances:");
-    for account in accounts_reference.lock().unwrap().deref() {
-        println!("{}: {}", account.name, account.balance.borrow());
+    for (name, balance) in accounts_reference.iter() {
+        println!("{}: {}", name, balance.lock().unwrap());
+    }
+
+    // Print the summary of how the work went.
+    let stats = stats_reference.lock().unwrap();
+    println!("\nSummary:");
+    println!("Transactions: {}", stats.num_transactions);
+    println!("Succeeded:    {}", stats.num_succeeded);
+    println!("Failed:       {}", stats.num_failed);
+    for reason in &stats.failures {
+        println!("  - {}", reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply a ledger (as raw lines) to a fresh set of accounts in the given
+    /// order and return the resulting balances. Runs single-threaded so the
+    /// outcome is purely a function of the dispatch order.
+    fn balances_after(lines: &[&str], order: Option<Vec<usize>>) -> BTreeMap<String, f64> {
+        let transactions: Vec<Transaction> =
+            lines.iter().map(|line| Transaction::from_line(line).unwrap()).collect();
+
+        let mut accounts: Accounts = BTreeMap::new();
+        for transaction in &transactions {
+            for name in [&transaction.withdraw_account, &transaction.deposit_account] {
+                if !name.is_empty() {
+                    accounts.entry(name.clone()).or_insert_with(|| Mutex::new(0.0));
+                }
+            }
+        }
+
+        // Keep the receiver alive so the event sends inside execute_transaction
+        // do not fail.
+        let (events, _events_rx) = mpsc::channel::<BalanceEvent>();
+        for transaction in OrderedIterator::new(&transactions, order) {
+            execute_transaction(transaction, &accounts, &events);
+        }
+
+        accounts
+            .iter()
+            .map(|(name, balance)| (name.clone(), *balance.lock().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_one_line_per_mode() {
+        let deposit = Transaction::from_line("1 - A1 10 deposit").unwrap();
+        assert!(matches!(deposit.mode, Mode::Deposit));
+        assert_eq!(deposit.withdraw_account, "");
+        assert_eq!(deposit.deposit_account, "A1");
+
+        let withdrawal = Transaction::from_line("2 A1 - 5 withdrawal").unwrap();
+        assert!(matches!(withdrawal.mode, Mode::Withdrawal));
+        assert_eq!(withdrawal.withdraw_account, "A1");
+        assert_eq!(withdrawal.deposit_account, "");
+
+        let transfer = Transaction::from_line("3 A1 A2 7 transfer").unwrap();
+        assert!(matches!(transfer.mode, Mode::Transfer));
+        assert_eq!(transfer.withdraw_account, "A1");
+        assert_eq!(transfer.deposit_account, "A2");
+        assert_eq!(transfer.amount, 7.0);
+    }
+
+    #[test]
+    fn reports_each_parse_error_variant() {
+        assert!(matches!(
+            Transaction::from_line("1 A1 A2 transfer"),
+            Err(ParseError::WrongFieldCount(4))
+        ));
+        assert!(matches!(
+            Transaction::from_line("x A1 A2 5 transfer"),
+            Err(ParseError::InvalidId(_))
+        ));
+        assert!(matches!(
+            Transaction::from_line("1 A1 A2 five transfer"),
+            Err(ParseError::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            Transaction::from_line("1 A1 A2 5 sideways"),
+            Err(ParseError::InvalidMode(_))
+        ));
+    }
+
+    #[test]
+    fn overdrawn_withdrawal_fails_and_leaves_balance_untouched() {
+        let mut accounts: Accounts = BTreeMap::new();
+        accounts.insert(String::from("A1"), Mutex::new(5.0));
+
+        let transaction = Transaction::from_line("1 A1 - 10 withdrawal").unwrap();
+        let (events, _events_rx) = mpsc::channel::<BalanceEvent>();
+        let result = execute_transaction(&transaction, &accounts, &events);
+
+        // The withdrawal is refused and the balance is left unchanged.
+        assert!(matches!(result, TransactionResult::Failed { .. }));
+        assert_eq!(*accounts["A1"].lock().unwrap(), 5.0);
+
+        // The failure is tallied, not counted as a success.
+        let mut stats = Stats::new();
+        stats.record(&result);
+        assert_eq!(stats.num_transactions, 1);
+        assert_eq!(stats.num_succeeded, 0);
+        assert_eq!(stats.num_failed, 1);
+        assert_eq!(stats.failures.len(), 1);
+    }
+
+    #[test]
+    fn pure_deposits_are_order_invariant() {
+        let lines = ["1 - A1 10 deposit", "2 - A2 5 deposit", "3 - A1 7 deposit"];
+        let natural = balances_after(&lines, None);
+        let reordered = balances_after(&lines, Some(vec![2, 0, 1]));
+        assert_eq!(natural, reordered);
+    }
+
+    #[test]
+    fn overdraft_gated_transfers_depend_on_order() {
+        // A1 only has funds once its seed deposit has been applied, so whether
+        // the transfer succeeds depends on whether it runs before or after it.
+        let lines = ["1 - A1 10 deposit", "2 A1 A2 10 transfer"];
+        let deposit_first = balances_after(&lines, Some(vec![0, 1]));
+        let transfer_first = balances_after(&lines, Some(vec![1, 0]));
+        assert_ne!(deposit_first, transfer_first);
     }
 }